@@ -0,0 +1,154 @@
+//! Support for the per-message `msg_name`/`msg_namelen` fields, used to learn the
+//! source address of a received datagram, or set the destination address of a sent one,
+//! on sockets that are not `connect()`ed.
+
+use libc::{c_void, sa_family_t, sockaddr, sockaddr_in, sockaddr_in6, socklen_t, AF_INET, AF_INET6};
+use std::mem;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+
+/// Backing storage for a `sockaddr`, large enough for either `sockaddr_in` or
+/// `sockaddr_in6`, suitable for use as the `msg_name` of a `MMsgHdr`.
+///
+/// Borrowed for the same `'buf` lifetime as the `MMsgHdr` it's attached to via
+/// `MMsgHdr::with_addr`; see that constructor's struct docs for why.
+#[repr(C)]
+pub struct SockAddrStorage {
+    storage: SockAddrStorageUnion,
+    /// The `msg_namelen` to hand the kernel: the full capacity of `storage` until
+    /// `set()` narrows it to the size of the address it wrote.
+    namelen: socklen_t,
+}
+#[repr(C)]
+union SockAddrStorageUnion {
+    v4: sockaddr_in,
+    v6: sockaddr_in6,
+}
+impl Default for SockAddrStorage {
+    fn default() -> SockAddrStorage {
+        SockAddrStorage {
+            storage: SockAddrStorageUnion {
+                v4: unsafe { mem::zeroed() },
+            },
+            namelen: SockAddrStorage::capacity(),
+        }
+    }
+}
+impl SockAddrStorage {
+    pub fn new() -> SockAddrStorage {
+        SockAddrStorage::default()
+    }
+
+    pub(crate) fn as_mut_ptr(&mut self) -> *mut sockaddr {
+        &mut self.storage as *mut SockAddrStorageUnion as *mut sockaddr
+    }
+
+    pub(crate) fn capacity() -> socklen_t {
+        mem::size_of::<SockAddrStorageUnion>() as socklen_t
+    }
+
+    /// The `msg_namelen` to pass to the kernel for this storage: its full capacity,
+    /// unless `set()` has narrowed it to a specific address's size.
+    pub(crate) fn namelen(&self) -> socklen_t {
+        self.namelen
+    }
+
+    /// Writes `addr` into this storage, narrowing `namelen()` to the size of the
+    /// resulting `sockaddr_in`/`sockaddr_in6`.
+    pub fn set(&mut self, addr: SocketAddr) {
+        self.namelen = self.write(addr);
+    }
+
+    fn write(&mut self, addr: SocketAddr) -> socklen_t {
+        match addr {
+            SocketAddr::V4(v4) => {
+                self.storage.v4 = sockaddr_in {
+                    sin_family: AF_INET as sa_family_t,
+                    sin_port: v4.port().to_be(),
+                    sin_addr: libc::in_addr {
+                        s_addr: u32::from_ne_bytes(v4.ip().octets()),
+                    },
+                    // `s_addr` above just mirrors the address octets into a u32 with
+                    // matching in-memory byte order; it is not a host/network-order
+                    // conversion.
+                    sin_zero: [0; 8],
+                };
+                mem::size_of::<sockaddr_in>() as socklen_t
+            }
+            SocketAddr::V6(v6) => {
+                self.storage.v6 = sockaddr_in6 {
+                    sin6_family: AF_INET6 as sa_family_t,
+                    sin6_port: v6.port().to_be(),
+                    sin6_flowinfo: v6.flowinfo(),
+                    sin6_addr: libc::in6_addr {
+                        s6_addr: v6.ip().octets(),
+                    },
+                    sin6_scope_id: v6.scope_id(),
+                };
+                mem::size_of::<sockaddr_in6>() as socklen_t
+            }
+        }
+    }
+}
+
+/// Decodes the address at `ptr` (a `sockaddr_in` or `sockaddr_in6`), given the
+/// `msg_namelen` the kernel reported, or `None` if no address was filled in.
+///
+/// `ptr` must be null, or point to memory that was originally written through
+/// `SockAddrStorage::as_mut_ptr()`, so that it is both aligned and large enough for
+/// the address family it reports.
+pub(crate) unsafe fn decode(ptr: *const c_void, namelen: socklen_t) -> Option<SocketAddr> {
+    if ptr.is_null() || namelen == 0 {
+        return None;
+    }
+    let family = (*(ptr as *const sockaddr_in)).sin_family as i32;
+    match family {
+        AF_INET => {
+            let v4 = *(ptr as *const sockaddr_in);
+            let ip = Ipv4Addr::from(v4.sin_addr.s_addr.to_ne_bytes());
+            Some(SocketAddr::V4(SocketAddrV4::new(ip, u16::from_be(v4.sin_port))))
+        }
+        AF_INET6 => {
+            let v6 = *(ptr as *const sockaddr_in6);
+            let ip = Ipv6Addr::from(v6.sin6_addr.s6_addr);
+            Some(SocketAddr::V6(SocketAddrV6::new(
+                ip,
+                u16::from_be(v6.sin6_port),
+                v6.sin6_flowinfo,
+                v6.sin6_scope_id,
+            )))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_v4() {
+        let mut storage = SockAddrStorage::new();
+        let addr = "127.0.0.1:4242".parse().unwrap();
+        storage.set(addr);
+        let namelen = storage.namelen();
+        let got = unsafe { decode(storage.as_mut_ptr() as *const c_void, namelen) };
+        assert_eq!(Some(addr), got);
+    }
+
+    #[test]
+    fn round_trips_v6() {
+        let mut storage = SockAddrStorage::new();
+        let addr = "[::1]:4242".parse().unwrap();
+        storage.set(addr);
+        let namelen = storage.namelen();
+        let got = unsafe { decode(storage.as_mut_ptr() as *const c_void, namelen) };
+        assert_eq!(Some(addr), got);
+    }
+
+    #[test]
+    fn no_address_when_namelen_is_zero() {
+        let mut storage = SockAddrStorage::new();
+        let got = unsafe { decode(storage.as_mut_ptr() as *const c_void, 0) };
+        assert_eq!(None, got);
+    }
+}
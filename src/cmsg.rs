@@ -0,0 +1,167 @@
+//! Support for building and parsing ancillary data (`cmsg`) buffers, as used by
+//! `msg_control`/`msg_controllen` in `MMsgHdr`.
+//!
+//! The layout of a control buffer is a sequence of `cmsghdr` headers, each immediately
+//! followed by its payload, with the next header starting at an offset rounded up to
+//! `sizeof(c_long)`. See `cmsg(3)` for details.
+
+use libc::{c_long, cmsghdr};
+use std::mem;
+
+/// Round `len` up to the alignment required between successive control messages.
+fn cmsg_align(len: usize) -> usize {
+    let align = mem::size_of::<c_long>();
+    (len + align - 1) & !(align - 1)
+}
+
+/// The value `cmsg_len` must be set to for a control message carrying a payload of
+/// `len` bytes.
+fn cmsg_len(len: usize) -> usize {
+    mem::size_of::<cmsghdr>() + len
+}
+
+/// The number of bytes a control message carrying a payload of `len` bytes occupies in
+/// a control buffer, once the header and trailing alignment padding are accounted for.
+pub fn cmsg_space(len: usize) -> usize {
+    mem::size_of::<cmsghdr>() + cmsg_align(len)
+}
+
+/// A single control message, as parsed out of a received control buffer.
+#[derive(Debug)]
+pub struct ControlMsg<'a> {
+    pub cmsg_level: libc::c_int,
+    pub cmsg_type: libc::c_int,
+    pub data: &'a [u8],
+}
+
+/// Builds a control buffer from `(cmsg_level, cmsg_type, payload)` tuples, ready to be
+/// handed to `MMsgHdr::with_control` for use with `sendmmsg`.
+///
+/// `buf` must be large enough to hold every message pushed to it; use `cmsg_space()` to
+/// size it. Any unused tail of `buf` is left zeroed, so callers should pass only the
+/// `finish()`ed prefix of `buf` on to `MMsgHdr::with_control`.
+pub struct CmsgBuilder<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+impl<'a> CmsgBuilder<'a> {
+    pub fn new(buf: &'a mut [u8]) -> CmsgBuilder<'a> {
+        for b in buf.iter_mut() {
+            *b = 0;
+        }
+        CmsgBuilder { buf, pos: 0 }
+    }
+
+    /// Append a control message. Panics if `buf` does not have enough remaining space.
+    pub fn push(
+        &mut self,
+        cmsg_level: libc::c_int,
+        cmsg_type: libc::c_int,
+        data: &[u8],
+    ) -> &mut Self {
+        let space = cmsg_space(data.len());
+        assert!(
+            self.pos + space <= self.buf.len(),
+            "cmsg buffer has {} bytes remaining, but this message needs {}",
+            self.buf.len() - self.pos,
+            space
+        );
+        // `self.buf` is a plain `&mut [u8]`, only guaranteed 1-byte alignment, so the
+        // `cmsghdr` at `self.pos` may not be aligned for `cmsghdr`'s fields: write it
+        // through an unaligned pointer write rather than a reference deref.
+        let hdr = cmsghdr {
+            cmsg_len: cmsg_len(data.len()) as _,
+            cmsg_level,
+            cmsg_type,
+        };
+        unsafe {
+            let hdr_ptr = self.buf.as_mut_ptr().add(self.pos) as *mut cmsghdr;
+            std::ptr::write_unaligned(hdr_ptr, hdr);
+            let data_ptr = self.buf.as_mut_ptr().add(self.pos + mem::size_of::<cmsghdr>());
+            std::ptr::copy_nonoverlapping(data.as_ptr(), data_ptr, data.len());
+        }
+        self.pos += space;
+        self
+    }
+
+    /// Finish building, returning the number of bytes written. The caller should slice
+    /// the buffer passed to `new()` down to this length before handing it to
+    /// `MMsgHdr::with_control`.
+    pub fn finish(&mut self) -> usize {
+        self.pos
+    }
+}
+
+/// Iterates the control messages present in a received control buffer.
+pub struct CmsgIter<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+impl<'a> CmsgIter<'a> {
+    pub(crate) fn new(buf: &'a [u8]) -> CmsgIter<'a> {
+        CmsgIter { buf, pos: 0 }
+    }
+}
+impl<'a> Iterator for CmsgIter<'a> {
+    type Item = ControlMsg<'a>;
+
+    fn next(&mut self) -> Option<ControlMsg<'a>> {
+        if self.pos + mem::size_of::<cmsghdr>() > self.buf.len() {
+            return None;
+        }
+        // As in `push`, `self.buf` is only guaranteed 1-byte alignment, so read the
+        // header through an unaligned pointer read rather than a reference deref.
+        let hdr = unsafe { std::ptr::read_unaligned(self.buf.as_ptr().add(self.pos) as *const cmsghdr) };
+        let len = hdr.cmsg_len as usize;
+        if len < mem::size_of::<cmsghdr>() || self.pos + len > self.buf.len() {
+            return None;
+        }
+        let data_start = self.pos + mem::size_of::<cmsghdr>();
+        let data = &self.buf[data_start..self.pos + len];
+        self.pos += cmsg_align(len);
+        Some(ControlMsg {
+            cmsg_level: hdr.cmsg_level,
+            cmsg_type: hdr.cmsg_type,
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_a_single_message() {
+        let mut buf = [0u8; 64];
+        let payload = [1u8, 2, 3, 4];
+        let len = CmsgBuilder::new(&mut buf)
+            .push(libc::SOL_SOCKET, libc::SCM_RIGHTS, &payload)
+            .finish();
+
+        let mut iter = CmsgIter::new(&buf[..len]);
+        let msg = iter.next().expect("one message");
+        assert_eq!(libc::SOL_SOCKET, msg.cmsg_level);
+        assert_eq!(libc::SCM_RIGHTS, msg.cmsg_type);
+        assert_eq!(&payload[..], msg.data);
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn round_trips_multiple_messages() {
+        let mut buf = [0u8; 128];
+        let a = [1u8, 2, 3];
+        let b = [4u8, 5, 6, 7, 8];
+        let len = CmsgBuilder::new(&mut buf)
+            .push(1, 2, &a)
+            .push(3, 4, &b)
+            .finish();
+
+        let mut iter = CmsgIter::new(&buf[..len]);
+        let first = iter.next().expect("first message");
+        assert_eq!(&a[..], first.data);
+        let second = iter.next().expect("second message");
+        assert_eq!(&b[..], second.data);
+        assert!(iter.next().is_none());
+    }
+}
@@ -3,10 +3,25 @@ extern crate iovec;
 #[macro_use]
 extern crate bitflags;
 
+mod addr;
+mod cmsg;
+mod fd;
+mod sys;
+mod timestamp;
+mod udp;
+
+pub use addr::SockAddrStorage;
+pub use cmsg::{cmsg_space, CmsgBuilder, CmsgIter, ControlMsg};
+pub use fd::{push_fds, recv_fds};
+pub use timestamp::SCM_TIMESTAMPNS;
+pub use udp::{push_segment_size, SOL_UDP, UDP_GRO, UDP_SEGMENT};
+
 use std::net;
 use std::io;
 use std::cmp;
-use libc::{c_int, ssize_t, msghdr, mmsghdr, timespec, MSG_DONTWAIT, MSG_CMSG_CLOEXEC,MSG_ERRQUEUE, MSG_PEEK, MSG_TRUNC, MSG_WAITFORONE};
+use std::fs::File;
+use std::os::unix::net::UnixDatagram;
+use libc::{c_int, ssize_t, timespec, MSG_DONTWAIT, MSG_CMSG_CLOEXEC,MSG_ERRQUEUE, MSG_PEEK, MSG_TRUNC, MSG_WAITFORONE};
 use std::os::unix::io::AsRawFd;
 use std::marker::PhantomData;
 use std::time;
@@ -23,46 +38,154 @@ bitflags! {
     }
 }
 
+/// A pending `recvmmsg`/`sendmmsg` batch entry: the iovec, flags, and (optionally) either
+/// a control-message buffer or an address buffer for one message; no constructor
+/// attaches both.
+///
+/// Any buffer passed in alongside `iovec` (by `with_control`/`with_addr`) is borrowed
+/// for the same `'buf` lifetime as `iovec`: the kernel is ultimately given a raw pointer
+/// into it, so it must not move for as long as this `MMsgHdr` exists.
 #[repr(C)]
 pub struct MMsgHdr<'buf> {
-    hdr: mmsghdr,
+    hdr: sys::RawMsgHdr,
     phantom: PhantomData<&'buf ()>,
 }
 impl<'buf> MMsgHdr<'buf> {
    pub fn new(iovec: &mut[&'buf mut iovec::IoVec], flags: MsgFlags) -> MMsgHdr<'buf> {
         let vlen = iovec.len();
-        // TODO:
-        //  - support 'control'
-        //  - support 'name'
         MMsgHdr {
-            hdr: mmsghdr {
-                msg_hdr: msghdr {
-                    msg_control: std::ptr::null_mut(),
-                    msg_controllen: 0,
-                    msg_flags: flags.bits(),
-                    msg_iov: iovec::unix::as_os_slice_mut(iovec).as_mut_ptr(),
-                    msg_iovlen: vlen,
-                    msg_name: std::ptr::null_mut(),
-                    msg_namelen: 0,
-                },
-                msg_len: 0,
-            },
+            hdr: sys::build(
+                iovec::unix::as_os_slice_mut(iovec).as_mut_ptr(),
+                vlen,
+                std::ptr::null_mut(),
+                0,
+                std::ptr::null_mut(),
+                0,
+                flags.bits(),
+            ),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Builds a `MMsgHdr` carrying ancillary data. `cmsg_buf` should be populated with
+    /// `CmsgBuilder` when sending, or left zeroed with room for the expected control
+    /// messages when receiving; either way its full length is handed to the kernel as
+    /// `msg_controllen`, so slice it to the length returned by `CmsgBuilder::finish()`
+    /// when sending a buffer that was allocated larger than it was filled.
+    ///
+    /// `cmsg_buf` shares `MMsgHdr`'s `'buf` lifetime; see the struct docs for why.
+    pub fn with_control(
+        iovec: &mut[&'buf mut iovec::IoVec],
+        flags: MsgFlags,
+        cmsg_buf: &'buf mut [u8],
+    ) -> MMsgHdr<'buf> {
+        let vlen = iovec.len();
+        MMsgHdr {
+            hdr: sys::build(
+                iovec::unix::as_os_slice_mut(iovec).as_mut_ptr(),
+                vlen,
+                std::ptr::null_mut(),
+                0,
+                cmsg_buf.as_mut_ptr() as *mut libc::c_void,
+                cmsg_buf.len(),
+                flags.bits(),
+            ),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Builds a `MMsgHdr` that reads or writes the per-message peer address via
+    /// `msg_name`. For `recvmmsg`, pass a freshly-created `SockAddrStorage` and read the
+    /// result back with `name()` once the call has returned. For `sendmmsg`, call
+    /// `SockAddrStorage::set()` with the destination address before constructing the
+    /// `MMsgHdr`.
+    ///
+    /// `addr` shares `MMsgHdr`'s `'buf` lifetime; see the struct docs for why.
+    pub fn with_addr(
+        iovec: &mut[&'buf mut iovec::IoVec],
+        flags: MsgFlags,
+        addr: &'buf mut SockAddrStorage,
+    ) -> MMsgHdr<'buf> {
+        let vlen = iovec.len();
+        let namelen = addr.namelen();
+        MMsgHdr {
+            hdr: sys::build(
+                iovec::unix::as_os_slice_mut(iovec).as_mut_ptr(),
+                vlen,
+                addr.as_mut_ptr() as *mut libc::c_void,
+                namelen,
+                std::ptr::null_mut(),
+                0,
+                flags.bits(),
+            ),
             phantom: PhantomData,
         }
     }
 
+    /// The peer address filled in to the `addr` passed to `with_addr()` by a
+    /// `recvmmsg()` call, or `None` if the kernel did not report one.
+    ///
+    /// This reads back through the raw `msg_name` pointer rather than taking `addr` as
+    /// a parameter, so that calling it doesn't require a second, overlapping borrow of
+    /// the storage `with_addr()` already borrowed for `'buf`.
+    pub fn name(&self) -> Option<net::SocketAddr> {
+        let (ptr, namelen) = sys::name(&self.hdr);
+        unsafe { addr::decode(ptr, namelen) }
+    }
+
     fn msg_len(&self) -> usize {
-        self.hdr.msg_len as usize
+        sys::msg_len(&self.hdr)
+    }
+
+    /// Iterates the control messages received into the buffer supplied via
+    /// `with_control()`, after a `recvmmsg()` call has populated this header.
+    pub fn cmsgs(&self) -> CmsgIter<'_> {
+        let (ptr, len) = sys::control(&self.hdr);
+        if ptr.is_null() {
+            CmsgIter::new(&[])
+        } else {
+            let buf = unsafe { std::slice::from_raw_parts(ptr as *const u8, len) };
+            CmsgIter::new(buf)
+        }
+    }
+
+    /// The segment size reported by a `SOL_UDP`/`UDP_GRO` control message received into
+    /// the buffer supplied via `with_control()`, if the kernel coalesced this datagram
+    /// under UDP GRO.
+    pub fn segment_size(&self) -> Option<u16> {
+        udp::segment_size(self.cmsgs())
+    }
+
+    /// The kernel's receive timestamp for this packet, from a `SOL_SOCKET`/
+    /// `SCM_TIMESTAMPNS` control message received into the buffer supplied via
+    /// `with_control()`. Requires `SO_TIMESTAMPNS` to have been set on the socket.
+    pub fn timestamp(&self) -> Option<time::Duration> {
+        timestamp::recv_timestamp(self.cmsgs())
+    }
+
+    /// The file descriptors passed via any `SOL_SOCKET`/`SCM_RIGHTS` control message
+    /// received into the buffer supplied via `with_control()`, as owned `File`s.
+    pub fn fds(&self) -> Vec<File> {
+        fd::recv_fds(self.cmsgs())
     }
 }
 
+mod sealed {
+    /// Sockets the batched mmsg-family syscalls apply to: any datagram socket, not just
+    /// `UdpSocket`. Sealed so this crate controls which fd-bearing types `MMsg` is
+    /// implemented for.
+    pub trait DatagramSocket: super::AsRawFd {}
+    impl DatagramSocket for super::net::UdpSocket {}
+    impl DatagramSocket for super::UnixDatagram {}
+}
+
 /// Methods will panic if given a timeout value that will not fit into the system `timespec` type
 trait MMsg {
     fn recvmmsg(&self, msgvec: &mut[MMsgHdr], flags: MsgFlags, timeout: Option<time::Duration>) -> io::Result<usize>;
     fn sendmmsg(&self, msgvec: &mut[MMsgHdr]) -> io::Result<usize>;
 }
 
-impl MMsg for net::UdpSocket {
+impl<S: sealed::DatagramSocket> MMsg for S {
     fn recvmmsg(&self, msgvec: &mut[MMsgHdr], flags: MsgFlags, timeout: Option<time::Duration>) -> io::Result<usize> {
         let len = cmp::min(msgvec.len(), max_len()) as u32;
         let mut t = timeout.map(|d| timespec {
@@ -75,9 +198,9 @@ impl MMsg for net::UdpSocket {
         };
         unsafe {
             let n = cvt({
-                libc::recvmmsg(
+                sys::recvmmsg(
                     self.as_raw_fd(),
-                    msgvec.as_mut_ptr() as *mut mmsghdr,
+                    msgvec.as_mut_ptr() as *mut sys::RawMsgHdr,
                     len,
                     flags.bits(),
                     tptr,
@@ -90,9 +213,9 @@ impl MMsg for net::UdpSocket {
         let len = cmp::min(msgvec.len(), max_len()) as u32;
         unsafe {
             let n = cvt({
-                libc::sendmmsg(
+                sys::sendmmsg(
                     self.as_raw_fd(),
-                    msgvec.as_mut_ptr() as *mut mmsghdr,
+                    msgvec.as_mut_ptr() as *mut sys::RawMsgHdr,
                     len,
                     0,
                 )
@@ -102,7 +225,7 @@ impl MMsg for net::UdpSocket {
     }
 }
 
-fn cvt(t: i32) -> io::Result<i32> {
+fn cvt(t: ssize_t) -> io::Result<ssize_t> {
     if t == -1 {
         Err(io::Error::last_os_error())
     } else {
@@ -119,7 +242,7 @@ fn max_len() -> usize {
     // intentionally showing odd behavior by rejecting any read with a size
     // larger than or equal to INT_MAX. To handle both of these the read
     // size is capped on both platforms.
-    if cfg!(target_os = "macos") {
+    if cfg!(any(target_os = "macos", target_os = "ios")) {
         <c_int>::max_value() as usize - 1
     } else {
         <ssize_t>::max_value() as usize
@@ -173,4 +296,50 @@ mod tests {
         assert_eq!(500, msgs[0].msg_len());
         sender.join().unwrap();
     }
+
+    #[test]
+    fn recvmmsg_reports_sender_address() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let receiver_addr = receiver.local_addr().unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_addr = sender.local_addr().unwrap();
+
+        let mut dest = SockAddrStorage::new();
+        dest.set(receiver_addr);
+        let mut a = [b'A'; 4];
+        let mut iov_a = [ (&mut a[..]).into() ];
+        let mut msgs = [ MMsgHdr::with_addr(&mut iov_a[..], MsgFlags::default(), &mut dest) ];
+        sender.sendmmsg(&mut msgs[..]).unwrap();
+
+        let mut buf = [0u8; 1500];
+        let mut iov = [ (&mut buf[..]).into() ];
+        let mut from = SockAddrStorage::new();
+        let mut msgs = [ MMsgHdr::with_addr(&mut iov[..], MsgFlags::default(), &mut from) ];
+        let count = receiver.recvmmsg(&mut msgs[..], MsgFlags::default(), None).unwrap();
+        assert_eq!(1, count);
+        assert_eq!(Some(sender_addr), msgs[0].name());
+    }
+
+    #[test]
+    fn unix_datagram_passes_fds_via_scm_rights() {
+        let (sender, receiver) = UnixDatagram::pair().unwrap();
+
+        let passed = std::fs::File::open("/dev/null").unwrap();
+        let mut send_buf = [0u8; 128];
+        let mut send_builder = CmsgBuilder::new(&mut send_buf);
+        push_fds(&mut send_builder, &[passed.as_raw_fd()]);
+        let len = send_builder.finish();
+        let mut a = [b'A'; 4];
+        let mut iov_a = [ (&mut a[..]).into() ];
+        let mut msgs = [ MMsgHdr::with_control(&mut iov_a[..], MsgFlags::default(), &mut send_buf[..len]) ];
+        sender.sendmmsg(&mut msgs[..]).unwrap();
+
+        let mut buf = [0u8; 128];
+        let mut iov = [ (&mut buf[..]).into() ];
+        let mut cmsg_buf = [0u8; 128];
+        let mut msgs = [ MMsgHdr::with_control(&mut iov[..], MsgFlags::default(), &mut cmsg_buf) ];
+        let count = receiver.recvmmsg(&mut msgs[..], MsgFlags::default(), None).unwrap();
+        assert_eq!(1, count);
+        assert_eq!(1, msgs[0].fds().len());
+    }
 }
@@ -0,0 +1,237 @@
+//! Platform-specific plumbing behind `MMsgHdr`/`MMsg`.
+//!
+//! Linux has `recvmmsg`/`sendmmsg`, which batch over an array of `mmsghdr`. Apple
+//! platforms have no such calls, but offer the same batched semantics via the
+//! private-but-stable `recvmsg_x`/`sendmsg_x` syscalls, which batch over an array of
+//! `msghdr_x` instead. This module picks the right on-the-wire struct and syscalls for
+//! the target platform behind a common interface, so the rest of the crate doesn't need
+//! to know which one it's talking to.
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+pub use self::darwin::*;
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+pub use self::linux::*;
+
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+mod linux {
+    use libc::{c_int, c_void, iovec, mmsghdr, msghdr, size_t, socklen_t, ssize_t, timespec};
+    use std::os::unix::io::RawFd;
+
+    pub type RawMsgHdr = mmsghdr;
+
+    pub fn build(
+        msg_iov: *mut iovec,
+        msg_iovlen: usize,
+        msg_name: *mut c_void,
+        msg_namelen: socklen_t,
+        msg_control: *mut c_void,
+        msg_controllen: size_t,
+        msg_flags: c_int,
+    ) -> RawMsgHdr {
+        mmsghdr {
+            msg_hdr: msghdr {
+                msg_name,
+                msg_namelen,
+                msg_iov,
+                msg_iovlen: msg_iovlen as _,
+                msg_control,
+                msg_controllen: msg_controllen as _,
+                msg_flags,
+            },
+            msg_len: 0,
+        }
+    }
+
+    pub fn control(hdr: &RawMsgHdr) -> (*const c_void, usize) {
+        (hdr.msg_hdr.msg_control, hdr.msg_hdr.msg_controllen)
+    }
+
+    pub fn name(hdr: &RawMsgHdr) -> (*const c_void, socklen_t) {
+        (hdr.msg_hdr.msg_name, hdr.msg_hdr.msg_namelen)
+    }
+
+    /// The number of bytes this header's syscall reported as sent/received.
+    pub fn msg_len(hdr: &RawMsgHdr) -> usize {
+        hdr.msg_len as usize
+    }
+
+    pub unsafe fn recvmmsg(
+        fd: RawFd,
+        msgvec: *mut RawMsgHdr,
+        vlen: u32,
+        flags: c_int,
+        timeout: *mut timespec,
+    ) -> ssize_t {
+        libc::recvmmsg(fd, msgvec, vlen, flags, timeout) as ssize_t
+    }
+
+    pub unsafe fn sendmmsg(fd: RawFd, msgvec: *mut RawMsgHdr, vlen: u32, flags: c_int) -> ssize_t {
+        libc::sendmmsg(fd, msgvec, vlen, flags) as ssize_t
+    }
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+mod darwin {
+    use libc::{c_int, c_uint, c_void, iovec, size_t, socklen_t, ssize_t};
+    use std::ffi::CString;
+    use std::mem;
+    use std::os::unix::io::RawFd;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Once;
+
+    /// Mirrors Apple's `struct msghdr_x`: a `msghdr` with an extra value-result
+    /// `msg_datalen` field reporting the number of payload bytes sent/received for this
+    /// particular entry of the batch.
+    #[repr(C)]
+    pub struct RawMsgHdr {
+        pub msg_name: *mut c_void,
+        pub msg_namelen: socklen_t,
+        pub msg_iov: *mut iovec,
+        pub msg_iovlen: c_int,
+        pub msg_control: *mut c_void,
+        pub msg_controllen: socklen_t,
+        pub msg_flags: c_int,
+        pub msg_datalen: size_t,
+    }
+
+    pub fn build(
+        msg_iov: *mut iovec,
+        msg_iovlen: usize,
+        msg_name: *mut c_void,
+        msg_namelen: socklen_t,
+        msg_control: *mut c_void,
+        msg_controllen: size_t,
+        msg_flags: c_int,
+    ) -> RawMsgHdr {
+        RawMsgHdr {
+            msg_name,
+            msg_namelen,
+            msg_iov,
+            msg_iovlen: msg_iovlen as c_int,
+            msg_control,
+            msg_controllen: msg_controllen as socklen_t,
+            msg_flags,
+            msg_datalen: 0,
+        }
+    }
+
+    pub fn control(hdr: &RawMsgHdr) -> (*const c_void, usize) {
+        (hdr.msg_control, hdr.msg_controllen as usize)
+    }
+
+    pub fn name(hdr: &RawMsgHdr) -> (*const c_void, socklen_t) {
+        (hdr.msg_name, hdr.msg_namelen)
+    }
+
+    pub fn msg_len(hdr: &RawMsgHdr) -> usize {
+        hdr.msg_datalen as usize
+    }
+
+    type RecvmsgXFn = unsafe extern "C" fn(c_int, *mut RawMsgHdr, c_uint, c_int) -> ssize_t;
+    type SendmsgXFn = unsafe extern "C" fn(c_int, *mut RawMsgHdr, c_uint, c_int) -> ssize_t;
+
+    /// Looks up `symbol` via `dlsym`, caching the result (including a failed lookup) for
+    /// the lifetime of the process. `recvmsg_x`/`sendmsg_x` are present since OS X 10.7,
+    /// but aren't in any header or the `libc` crate, so there's nothing to link against
+    /// directly; probing for them at runtime also lets us fall back gracefully on older
+    /// systems where they don't exist at all.
+    fn resolve(symbol: &'static str, cache: &'static AtomicUsize, once: &'static Once) -> usize {
+        once.call_once(|| {
+            let name = CString::new(symbol).unwrap();
+            let addr = unsafe { libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr()) } as usize;
+            cache.store(addr, Ordering::SeqCst);
+        });
+        cache.load(Ordering::SeqCst)
+    }
+
+    static RECVMSG_X_ADDR: AtomicUsize = AtomicUsize::new(0);
+    static RECVMSG_X_ONCE: Once = Once::new();
+    static SENDMSG_X_ADDR: AtomicUsize = AtomicUsize::new(0);
+    static SENDMSG_X_ONCE: Once = Once::new();
+
+    fn recvmsg_x() -> Option<RecvmsgXFn> {
+        let addr = resolve("recvmsg_x", &RECVMSG_X_ADDR, &RECVMSG_X_ONCE);
+        if addr == 0 {
+            None
+        } else {
+            Some(unsafe { mem::transmute::<*const (), RecvmsgXFn>(addr as *const ()) })
+        }
+    }
+
+    fn sendmsg_x() -> Option<SendmsgXFn> {
+        let addr = resolve("sendmsg_x", &SENDMSG_X_ADDR, &SENDMSG_X_ONCE);
+        if addr == 0 {
+            None
+        } else {
+            Some(unsafe { mem::transmute::<*const (), SendmsgXFn>(addr as *const ()) })
+        }
+    }
+
+    /// Builds a plain `libc::msghdr` borrowing the name/iov/control fields out of a
+    /// single `RawMsgHdr` batch entry, for the scalar-loop fallback.
+    unsafe fn as_msghdr(hdr: &RawMsgHdr) -> libc::msghdr {
+        libc::msghdr {
+            msg_name: hdr.msg_name,
+            msg_namelen: hdr.msg_namelen,
+            msg_iov: hdr.msg_iov,
+            msg_iovlen: hdr.msg_iovlen,
+            msg_control: hdr.msg_control,
+            msg_controllen: hdr.msg_controllen,
+            msg_flags: hdr.msg_flags,
+        }
+    }
+
+    /// Scalar fallback for systems where `recvmsg_x` isn't linked: call `recvmsg` once
+    /// per batch entry, stopping at the first error (returning it only if no entry has
+    /// succeeded yet, matching `recvmmsg`'s convention).
+    unsafe fn recvmmsg_scalar(fd: RawFd, msgvec: &mut [RawMsgHdr], flags: c_int) -> ssize_t {
+        for (i, hdr) in msgvec.iter_mut().enumerate() {
+            let mut m = as_msghdr(hdr);
+            let n = libc::recvmsg(fd, &mut m, flags);
+            if n < 0 {
+                return if i == 0 { n } else { i as ssize_t };
+            }
+            hdr.msg_namelen = m.msg_namelen;
+            hdr.msg_controllen = m.msg_controllen;
+            hdr.msg_flags = m.msg_flags;
+            hdr.msg_datalen = n as size_t;
+        }
+        msgvec.len() as ssize_t
+    }
+
+    unsafe fn sendmmsg_scalar(fd: RawFd, msgvec: &mut [RawMsgHdr], flags: c_int) -> ssize_t {
+        for (i, hdr) in msgvec.iter_mut().enumerate() {
+            let m = as_msghdr(hdr);
+            let n = libc::sendmsg(fd, &m, flags);
+            if n < 0 {
+                return if i == 0 { n } else { i as ssize_t };
+            }
+            hdr.msg_datalen = n as size_t;
+        }
+        msgvec.len() as ssize_t
+    }
+
+    /// `timeout` has no equivalent in `recvmsg_x`/`recvmsg` and is ignored; callers
+    /// wanting a receive deadline on Apple platforms should use `SO_RCVTIMEO` instead.
+    pub unsafe fn recvmmsg(
+        fd: RawFd,
+        msgvec: *mut RawMsgHdr,
+        vlen: u32,
+        flags: c_int,
+        _timeout: *mut libc::timespec,
+    ) -> ssize_t {
+        let msgvec = std::slice::from_raw_parts_mut(msgvec, vlen as usize);
+        match recvmsg_x() {
+            Some(f) => f(fd, msgvec.as_mut_ptr(), vlen as c_uint, flags),
+            None => recvmmsg_scalar(fd, msgvec, flags),
+        }
+    }
+
+    pub unsafe fn sendmmsg(fd: RawFd, msgvec: *mut RawMsgHdr, vlen: u32, flags: c_int) -> ssize_t {
+        let msgvec = std::slice::from_raw_parts_mut(msgvec, vlen as usize);
+        match sendmsg_x() {
+            Some(f) => f(fd, msgvec.as_mut_ptr(), vlen as c_uint, flags),
+            None => sendmmsg_scalar(fd, msgvec, flags),
+        }
+    }
+}
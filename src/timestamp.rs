@@ -0,0 +1,53 @@
+//! Per-packet kernel receive timestamps (`SO_TIMESTAMPNS`), built on top of the `cmsg`
+//! API.
+//!
+//! Once the caller has set `SO_TIMESTAMPNS` on a socket, the kernel attaches a
+//! `SOL_SOCKET`/`SCM_TIMESTAMPNS` control message carrying a `timespec` to each received
+//! datagram, giving the time it arrived rather than just the fact that it did.
+
+use super::cmsg::CmsgIter;
+use libc::{timespec, SOL_SOCKET};
+use std::mem;
+use std::time::Duration;
+
+pub use libc::SCM_TIMESTAMPNS;
+
+/// Reads the `SOL_SOCKET`/`SCM_TIMESTAMPNS` control message out of `cmsgs`, if present,
+/// as the kernel's receive timestamp for the packet.
+pub fn recv_timestamp(cmsgs: CmsgIter) -> Option<Duration> {
+    for msg in cmsgs {
+        if msg.cmsg_level == SOL_SOCKET
+            && msg.cmsg_type == SCM_TIMESTAMPNS
+            && msg.data.len() == mem::size_of::<timespec>()
+        {
+            let ts = unsafe { (msg.data.as_ptr() as *const timespec).read_unaligned() };
+            return Some(Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::cmsg::CmsgBuilder;
+
+    #[test]
+    fn reads_back_pushed_timestamp() {
+        let ts = timespec { tv_sec: 12, tv_nsec: 345 };
+        let bytes = unsafe {
+            std::slice::from_raw_parts(&ts as *const timespec as *const u8, mem::size_of::<timespec>())
+        };
+        let mut buf = [0u8; 64];
+        let mut builder = CmsgBuilder::new(&mut buf[..]);
+        builder.push(SOL_SOCKET, SCM_TIMESTAMPNS, bytes);
+        let len = builder.finish();
+        assert_eq!(Some(Duration::new(12, 345)), recv_timestamp(CmsgIter::new(&buf[..len])));
+    }
+
+    #[test]
+    fn none_when_no_timestamp_cmsg_present() {
+        let buf = [0u8; 0];
+        assert_eq!(None, recv_timestamp(CmsgIter::new(&buf)));
+    }
+}
@@ -0,0 +1,81 @@
+//! UDP generic segmentation/receive offload (GSO/GRO) support, built on top of the
+//! `cmsg` API.
+//!
+//! `UDP_SEGMENT` asks the kernel to split a single large payload into fixed-size
+//! datagrams (plus a final short one) when sending, so one `sendmmsg` call can push far
+//! more packets than its vector length alone allows. `UDP_GRO` is the receive-side
+//! counterpart: the kernel coalesces consecutive same-size datagrams from one sender
+//! into a single large buffer and reports the original segment size via a cmsg, so the
+//! caller can split it back up.
+
+use super::cmsg::{CmsgBuilder, CmsgIter};
+use libc::{c_int, IPPROTO_UDP};
+
+pub const SOL_UDP: c_int = IPPROTO_UDP;
+pub const UDP_SEGMENT: c_int = 103;
+pub const UDP_GRO: c_int = 104;
+
+/// Appends a `SOL_UDP`/`UDP_SEGMENT` control message to `builder`, asking the kernel to
+/// transmit the payload as packets of `segment_size` bytes (plus a final short one).
+pub fn push_segment_size<'a, 'b>(
+    builder: &'a mut CmsgBuilder<'b>,
+    segment_size: u16,
+) -> &'a mut CmsgBuilder<'b> {
+    builder.push(SOL_UDP, UDP_SEGMENT, &segment_size.to_ne_bytes())
+}
+
+/// Reads the `SOL_UDP`/`UDP_GRO` control message out of `cmsgs`, if present, giving the
+/// segment size a received, GRO-coalesced buffer should be split into.
+pub fn segment_size(cmsgs: CmsgIter) -> Option<u16> {
+    for msg in cmsgs {
+        if msg.cmsg_level != SOL_UDP || msg.cmsg_type != UDP_GRO {
+            continue;
+        }
+        // Linux reports the GRO segment size as a C `int`; a bare `u16` payload is also
+        // accepted.
+        if msg.data.len() == std::mem::size_of::<c_int>() {
+            let mut bytes = [0u8; std::mem::size_of::<c_int>()];
+            bytes.copy_from_slice(msg.data);
+            return Some(c_int::from_ne_bytes(bytes) as u16);
+        }
+        if msg.data.len() == 2 {
+            let mut bytes = [0u8; 2];
+            bytes.copy_from_slice(msg.data);
+            return Some(u16::from_ne_bytes(bytes));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_segment_size_emits_udp_segment_cmsg() {
+        let mut buf = [0u8; 64];
+        let mut builder = CmsgBuilder::new(&mut buf[..]);
+        push_segment_size(&mut builder, 1350);
+        let len = builder.finish();
+
+        let msg = CmsgIter::new(&buf[..len]).next().unwrap();
+        assert_eq!(SOL_UDP, msg.cmsg_level);
+        assert_eq!(UDP_SEGMENT, msg.cmsg_type);
+        assert_eq!(&1350u16.to_ne_bytes()[..], msg.data);
+    }
+
+    #[test]
+    fn reads_back_udp_gro_segment_size() {
+        let mut buf = [0u8; 64];
+        let mut builder = CmsgBuilder::new(&mut buf[..]);
+        builder.push(SOL_UDP, UDP_GRO, &1350u16.to_ne_bytes());
+        let len = builder.finish();
+        assert_eq!(Some(1350), segment_size(CmsgIter::new(&buf[..len])));
+    }
+
+    #[test]
+    fn none_when_no_gro_cmsg_present() {
+        let buf = [0u8; 0];
+        assert_eq!(None, segment_size(CmsgIter::new(&buf)));
+    }
+}